@@ -0,0 +1,177 @@
+//! Per-connection state and the main per-client message loop.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use failure::Error;
+
+use crate::io::seq_packet::SeqPacketStream;
+use crate::lxcseccomp::ProxyMessageBuffer;
+use crate::policy::{Handler, Policy};
+use crate::socket::AsyncSeqPacketSocket;
+
+/// Maximum `lxc.seccomp.notify.cookie` length we're willing to buffer.
+const MAX_COOKIE_SIZE: usize = 4096;
+
+/// Close the connection after this many `recv()` errors in a row.
+///
+/// A single bad message (eg. a cookie-length mismatch) is worth answering and moving on from, but
+/// a socket that keeps erroring without ever returning clean EOF - a dead peer, a confused
+/// monitor - would otherwise spin this loop as fast as `await` lets it, burning CPU and flooding
+/// stderr forever.
+const MAX_CONSECUTIVE_RECV_ERRORS: u32 = 10;
+
+/// State for a single connection from the lxc monitor.
+pub struct Client {
+    socket: AsyncSeqPacketSocket,
+    policy_dir: Option<Arc<PathBuf>>,
+
+    /// Built from the first message's cookie; `None` until then.
+    policy: Option<Policy>,
+}
+
+impl Client {
+    /// Create a client with no `--policy-dir` configured.
+    pub fn new(stream: SeqPacketStream) -> Self {
+        Self::with_policy_dir(stream, None)
+    }
+
+    /// Create a client that resolves named cookie policies against `policy_dir`.
+    pub fn with_policy_dir(stream: SeqPacketStream, policy_dir: Option<Arc<PathBuf>>) -> Self {
+        Self {
+            socket: AsyncSeqPacketSocket::new(stream),
+            policy_dir,
+            policy: None,
+        }
+    }
+
+    /// Run the per-connection message loop.
+    ///
+    /// A container task blocks on its seccomp notification until we answer it, so every message
+    /// we successfully receive *must* get a reply, even if something along the way - parsing a
+    /// message, parsing the policy cookie, running a handler - goes wrong. Borrowing the "always
+    /// answer" discipline from liblxc's own notify handler: recv/parse errors, handler errors and
+    /// handler panics all fall back to the default `-ENOSYS` response instead of tearing down the
+    /// socket; the connection is closed on a true EOF from `recv()`, or after
+    /// [`MAX_CONSECUTIVE_RECV_ERRORS`] recv errors in a row, so a persistently broken socket can't
+    /// spin this loop forever.
+    pub async fn main(mut self) {
+        let mut msg = ProxyMessageBuffer::new(MAX_COOKIE_SIZE);
+        let mut consecutive_recv_errors = 0u32;
+
+        loop {
+            match msg.recv(&self.socket).await {
+                Ok(true) => consecutive_recv_errors = 0,
+                Ok(false) => return, // EOF: the monitor closed the connection
+                Err(err) => {
+                    consecutive_recv_errors += 1;
+
+                    // The vectored read underlying recv() may well have already filled in
+                    // `seccomp_notif` (and thus a usable id) before a later validation step
+                    // (cookie length, reserved bytes, ...) failed, so there's a real message to
+                    // answer here, not just noise to log.
+                    eprintln!(
+                        "error receiving seccomp notification, answering with the default response: {}",
+                        err
+                    );
+                    msg.reset_response();
+                    if let Err(err) = msg.respond(&self.socket).await {
+                        eprintln!("failed to send seccomp response: {}", err);
+                    }
+
+                    if consecutive_recv_errors >= MAX_CONSECUTIVE_RECV_ERRORS {
+                        eprintln!(
+                            "{} consecutive recv errors, closing connection",
+                            consecutive_recv_errors
+                        );
+                        return;
+                    }
+
+                    continue;
+                }
+            }
+
+            // Catch handler panics here too: a panicking handler must not unwind past this point
+            // and skip respond(), or the container task would block on its notification forever.
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                self.ensure_policy(&msg);
+                self.dispatch(&mut msg)
+            }));
+
+            match result {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => {
+                    eprintln!(
+                        "handler error, answering with the default response: {}",
+                        err
+                    );
+                    msg.reset_response();
+                }
+                Err(panic) => {
+                    eprintln!(
+                        "handler panicked, answering with the default response: {}",
+                        panic_message(&*panic)
+                    );
+                    msg.reset_response();
+                }
+            }
+
+            if let Err(err) = msg.respond(&self.socket).await {
+                eprintln!("failed to send seccomp response: {}", err);
+            }
+        }
+    }
+
+    /// Build the client's policy from the first message's cookie, if not already done.
+    ///
+    /// A cookie that fails to parse denies every syscall for the rest of the connection rather
+    /// than aborting it - the container just gets `-ENOSYS` for everything, same as a client with
+    /// no policy at all.
+    fn ensure_policy(&mut self, msg: &ProxyMessageBuffer) {
+        if self.policy.is_some() {
+            return;
+        }
+
+        let policy = Policy::from_cookie(msg.cookie(), self.policy_dir.as_deref().map(PathBuf::as_path))
+            .unwrap_or_else(|err| {
+                eprintln!(
+                    "failed to parse policy cookie, denying all syscalls for this client: {}",
+                    err
+                );
+                Policy::deny_all()
+            });
+
+        self.policy = Some(policy);
+    }
+
+    /// Run the handler for this message's syscall, if its arch/nr is enabled by the client's
+    /// policy. The response already defaults to `-ENOSYS` (set up by `recv()`), so syscalls with
+    /// no matching handler, or that aren't enabled, are rejected simply by doing nothing.
+    fn dispatch(&self, msg: &mut ProxyMessageBuffer) -> Result<(), Error> {
+        let arch = msg.request().data.arch;
+        let nr = msg.request().data.nr;
+
+        let policy = self
+            .policy
+            .as_ref()
+            .expect("policy is initialized before the first dispatch");
+
+        match policy.handler_for(arch, nr) {
+            Some(Handler::Mount) => crate::sys_mount::handle(msg, msg.init_pid()),
+            Some(Handler::Mknod) => crate::sys_mknod::handle(msg),
+            Some(Handler::Quotactl) => crate::sys_quotactl::handle(msg),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Best-effort extraction of a human-readable message from a caught panic payload.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
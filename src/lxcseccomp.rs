@@ -2,6 +2,7 @@
 
 use std::convert::TryFrom;
 use std::mem;
+use std::os::unix::io::AsRawFd;
 
 use failure::{bail, Error};
 use lazy_static::lazy_static;
@@ -58,6 +59,7 @@ pub struct ProxyMessageBuffer {
 
     pid_fd: Option<Fd>,
     mem_fd: Option<Fd>,
+    notify_fd: Option<Fd>,
 }
 
 unsafe fn io_vec_mut<T>(value: &mut T) -> IoVecMut {
@@ -78,6 +80,48 @@ lazy_static! {
     static ref SECCOMP_SIZES: SeccompNotifSizes = SeccompNotifSizes::get_checked()
         .map_err(|e| panic!("{}\nrefusing to run", e))
         .unwrap();
+
+    /// Whether the running kernel understands `SECCOMP_USER_NOTIF_FLAG_CONTINUE`.
+    ///
+    /// Probed once, on first use. `struct seccomp_notif_resp` has had its `flags` member since the
+    /// very first (5.0) version of the notify API, so its size can't tell a CONTINUE-capable
+    /// kernel (5.5+) apart from an older one that merely ignores unknown flag bits - the flag is
+    /// just a new bit value for an existing field. We instead compare `uname()`'s reported kernel
+    /// version directly against 5.5, the version that introduced the flag.
+    static ref SECCOMP_SUPPORTS_CONTINUE: bool = kernel_supports_continue();
+}
+
+/// Flag for [`SeccompNotifResp::flags`] telling the kernel to continue (not emulate) the syscall.
+///
+/// Added in Linux 5.5. See `respond_continue()` for the TOCTOU caveat around its use.
+const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1;
+
+/// Whether the running kernel is 5.5 or newer, ie. new enough to understand
+/// `SECCOMP_USER_NOTIF_FLAG_CONTINUE`.
+fn kernel_supports_continue() -> bool {
+    parse_uname_release().map_or(false, |(major, minor)| (major, minor) >= (5, 5))
+}
+
+/// Get and parse the `(major, minor)` kernel version from `uname(2)`.
+fn parse_uname_release() -> Option<(u32, u32)> {
+    let mut uts: libc::utsname = unsafe { mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    parse_release(release.to_str().ok()?)
+}
+
+/// Parse the `(major, minor)` kernel version out of a `uname(2)` release string.
+///
+/// Release strings look like `"5.5.0-generic"` or `"5.15.30-2-pve"`; we only need the first two
+/// numeric components.
+fn parse_release(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| c == '.' || c == '-');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
 }
 
 impl ProxyMessageBuffer {
@@ -98,6 +142,7 @@ impl ProxyMessageBuffer {
             seccomp_packet_size,
             pid_fd: None,
             mem_fd: None,
+            notify_fd: None,
         }
     }
 
@@ -123,7 +168,12 @@ impl ProxyMessageBuffer {
             self.cookie_buf.set_len(0);
         }
 
-        let (size, fds) = socket.recv_fds_vectored(&mut iovec, 2).await?;
+        // liblxc sends the `pid_fd` and `mem_fd` on every message, and, starting with a recent
+        // enough liblxc, the seccomp notify fd itself as a third fd so we can re-validate a
+        // notification id via `SECCOMP_IOCTL_NOTIF_ID_VALID` after reading container memory.
+        // Older liblxc versions simply won't send a third fd, in which case `notify_fd` stays
+        // `None` and `notif_id_valid()` degenerates to "assume still valid".
+        let (size, fds) = socket.recv_fds_vectored(&mut iovec, 3).await?;
         if size == 0 {
             return Ok(false);
         }
@@ -133,6 +183,7 @@ impl ProxyMessageBuffer {
         let mut fds = fds.into_iter();
         self.pid_fd = fds.next();
         self.mem_fd = fds.next();
+        self.notify_fd = fds.next();
         if self.mem_fd.is_none() {
             self.drop_fds();
             bail!("missing file descriptors with proxied seccomp message");
@@ -144,6 +195,7 @@ impl ProxyMessageBuffer {
     pub fn drop_fds(&mut self) {
         self.pid_fd = None;
         self.mem_fd = None;
+        self.notify_fd = None;
     }
 
     /// Send the current data as response.
@@ -166,6 +218,60 @@ impl ProxyMessageBuffer {
         resp.flags = 0;
     }
 
+    /// Reset the response to the default `-ENOSYS` answer.
+    ///
+    /// Handlers normally fill in their own response via [`response_mut()`](Self::response_mut),
+    /// but any caller that gives up on a message partway through (a handler error, an unhandled
+    /// syscall, a failed policy lookup) can call this to fall back to the safe default instead of
+    /// sending back whatever partial state the response buffer is in.
+    pub fn reset_response(&mut self) {
+        self.prepare_response();
+    }
+
+    /// Whether the kernel we're running on supports `SECCOMP_USER_NOTIF_FLAG_CONTINUE`.
+    ///
+    /// [`respond_continue`](ProxyMessageBuffer::respond_continue) already checks this and fails if
+    /// it's `false`, so calling this first is optional; handlers that can decide to emulate or
+    /// reject the syscall more cheaply than preparing to continue it may still want to check here
+    /// first to skip that work.
+    pub fn supports_continue() -> bool {
+        *SECCOMP_SUPPORTS_CONTINUE
+    }
+
+    /// Tell the kernel to let the original syscall run instead of emulating it.
+    ///
+    /// This sets `flags = SECCOMP_USER_NOTIF_FLAG_CONTINUE` with `error = 0`, `val = 0`, which
+    /// makes the kernel resume the intercepted syscall exactly as if our filter had returned
+    /// `SECCOMP_RET_ALLOW`.
+    ///
+    /// # TOCTOU warning
+    ///
+    /// The kernel re-reads the syscall's arguments from the container process at the point it
+    /// actually resumes the call, *not* the values we inspected via [`request()`](Self::request)
+    /// or the checked-memory helpers. A second thread in the container can rewrite argument
+    /// memory (or swap a path component) between our decision and the kernel's re-execution of
+    /// the syscall. Only use `respond_continue()` for syscalls whose arguments the proxy does not
+    /// need to trust for its security decision; if the handler's logic depends on the exact bytes
+    /// it read, it must emulate the syscall itself instead of continuing it.
+    ///
+    /// Fails without touching the response buffer if [`supports_continue()`](Self::supports_continue)
+    /// is `false`; the caller must fall back to emulating or rejecting the syscall itself in that
+    /// case.
+    pub fn respond_continue(&mut self) -> Result<(), Error> {
+        if !Self::supports_continue() {
+            bail!("kernel does not support SECCOMP_USER_NOTIF_FLAG_CONTINUE");
+        }
+
+        let id = self.request().id;
+        let resp = self.response_mut();
+        resp.id = id;
+        resp.val = 0;
+        resp.error = 0;
+        resp.flags = SECCOMP_USER_NOTIF_FLAG_CONTINUE;
+
+        Ok(())
+    }
+
     /// Called by with_io_slice after the callback returned the new size. This verifies that
     /// there's enough data available.
     pub fn set_len(&mut self, len: usize) -> Result<(), Error> {
@@ -251,4 +357,80 @@ impl ProxyMessageBuffer {
     pub fn cookie(&self) -> &[u8] {
         &self.cookie_buf
     }
+
+    /// Get the container's `/proc/pid/mem` file descriptor, if one was sent with this message.
+    pub fn mem_fd(&self) -> Option<&Fd> {
+        self.mem_fd.as_ref()
+    }
+
+    /// Re-validate that the current notification is still alive.
+    ///
+    /// Handlers which dereference pointer arguments via the container's `mem_fd` (see the `mem`
+    /// module) must call this *after* copying out the data they need and *before* acting on it.
+    /// Without this check a process could answer the notification itself (making the kernel
+    /// reuse the `id`) or simply exit, and we'd otherwise act on memory contents belonging to an
+    /// unrelated, later syscall.
+    ///
+    /// If the lxc monitor didn't send us a notify fd (older liblxc), we have no way to ask the
+    /// kernel and optimistically report the notification as still valid.
+    pub fn notif_id_valid(&self) -> bool {
+        let notify_fd = match &self.notify_fd {
+            Some(fd) => fd,
+            None => return true,
+        };
+
+        let id = self.request().id;
+        let rc = unsafe {
+            libc::ioctl(
+                notify_fd.as_raw_fd(),
+                SECCOMP_IOCTL_NOTIF_ID_VALID,
+                &id as *const u64,
+            )
+        };
+
+        rc == 0
+    }
+}
+
+/// `ioctl(2)` request code for `SECCOMP_IOCTL_NOTIF_ID_VALID`.
+///
+/// Equivalent to the kernel's `_IOW(SECCOMP_IOCTL_MAGIC, 2, __u64)`. Not (yet) exposed by the
+/// `libc` crate, so we spell out the encoding ourselves.
+const SECCOMP_IOCTL_NOTIF_ID_VALID: libc::c_ulong = 0x4008_2102;
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_release, SECCOMP_IOCTL_NOTIF_ID_VALID};
+
+    #[test]
+    fn parse_release_handles_documented_formats() {
+        let cases = [
+            ("5.5.0-generic", Some((5, 5))),
+            ("5.15.30-2-pve", Some((5, 15))),
+            ("4.19.0", Some((4, 19))),
+            ("6.1.55-1-amd64", Some((6, 1))),
+            ("not-a-kernel-release", None),
+            ("", None),
+        ];
+
+        for (release, expected) in cases {
+            assert_eq!(parse_release(release), expected, "release: {:?}", release);
+        }
+    }
+
+    /// Recompute the kernel's `_IOW(SECCOMP_IOCTL_MAGIC, 2, __u64)` encoding independently of the
+    /// hardcoded constant above, so a transposed digit or wrong magic byte in the literal (like
+    /// the `0x57` ('W') vs `0x21` ('!') mixup this caught) fails loudly instead of silently making
+    /// every `notif_id_valid()` call on a real notify fd return `ENOTTY`/`false`.
+    #[test]
+    fn notif_id_valid_ioctl_matches_kernel_encoding() {
+        const IOC_WRITE: libc::c_ulong = 1;
+        const SECCOMP_IOCTL_MAGIC: libc::c_ulong = b'!' as libc::c_ulong;
+        const NR: libc::c_ulong = 2;
+        let size = std::mem::size_of::<u64>() as libc::c_ulong;
+
+        let expected = (IOC_WRITE << 30) | (size << 16) | (SECCOMP_IOCTL_MAGIC << 8) | NR;
+
+        assert_eq!(SECCOMP_IOCTL_NOTIF_ID_VALID, expected);
+    }
 }
@@ -3,6 +3,8 @@ use std::future::Future;
 use std::io as StdIo;
 use std::io::{stderr, stdout, Write};
 use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use failure::{bail, format_err, Error};
 use nix::sys::socket::SockAddr;
@@ -17,11 +19,14 @@ pub mod error;
 pub mod fork;
 pub mod io;
 pub mod lxcseccomp;
+pub mod mem;
 pub mod nsfd;
+pub mod policy;
 pub mod poll_fn;
 pub mod process;
 pub mod seccomp;
 pub mod sys_mknod;
+pub mod sys_mount;
 pub mod sys_quotactl;
 pub mod syscall;
 pub mod tools;
@@ -39,9 +44,11 @@ fn usage(status: i32, program: &OsStr, out: &mut dyn Write) -> ! {
         concat!(
             "[options] SOCKET_PATH\n",
             "options:\n",
-            "    -h, --help      show this help message\n",
-            "    --system        \
+            "    -h, --help        show this help message\n",
+            "    --system          \
                      run as systemd daemon (use sd_notify() when ready to accept connections)\n",
+            "    --policy-dir DIR  \
+                     load named per-container syscall policies referenced by cookies from DIR\n",
         )
         .as_bytes(),
     );
@@ -53,9 +60,10 @@ fn main() {
     let program = args.next().unwrap(); // program name always exists
 
     let mut use_sd_notify = false;
+    let mut policy_dir = None;
     let mut path = None;
 
-    for arg in &mut args {
+    while let Some(arg) = args.next() {
         if arg == "-h" || arg == "--help" {
             usage(0, &program, &mut stdout());
         }
@@ -64,6 +72,14 @@ fn main() {
             break;
         } else if arg == "--system" {
             use_sd_notify = true;
+        } else if arg == "--policy-dir" {
+            match args.next() {
+                Some(dir) => policy_dir = Some(PathBuf::from(dir)),
+                None => {
+                    eprintln!("--policy-dir requires an argument");
+                    usage(1, &program, &mut stderr());
+                }
+            }
         } else {
             let bytes = arg.as_bytes();
             if bytes.starts_with(b"-") {
@@ -92,13 +108,17 @@ fn main() {
 
     let mut rt = tokio::runtime::Runtime::new().expect("failed to spawn tokio runtime");
 
-    if let Err(err) = rt.block_on(do_main(use_sd_notify, path)) {
+    if let Err(err) = rt.block_on(do_main(use_sd_notify, policy_dir, path)) {
         eprintln!("error: {}", err);
         std::process::exit(1);
     }
 }
 
-async fn do_main(use_sd_notify: bool, socket_path: OsString) -> Result<(), Error> {
+async fn do_main(
+    use_sd_notify: bool,
+    policy_dir: Option<PathBuf>,
+    socket_path: OsString,
+) -> Result<(), Error> {
     match std::fs::remove_file(&socket_path) {
         Ok(_) => (),
         Err(ref e) if e.kind() == StdIo::ErrorKind::NotFound => (), // Ok
@@ -115,9 +135,11 @@ async fn do_main(use_sd_notify: bool, socket_path: OsString) -> Result<(), Error
         notify_systemd()?;
     }
 
+    let policy_dir = policy_dir.map(Arc::new);
+
     loop {
         let client = listener.accept().await?;
-        let client = client::Client::new(client);
+        let client = client::Client::with_policy_dir(client, policy_dir.clone());
         spawn(client.main());
     }
 }
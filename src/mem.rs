@@ -0,0 +1,213 @@
+//! Helpers for reading a container process' memory via its `/proc/pid/mem` file descriptor.
+//!
+//! Handlers for syscalls like `mount`, `mknod` or `init_module` need to dereference pointer
+//! arguments out of [`SeccompNotif::data::args`](crate::seccomp::SeccompNotif). The lxc monitor
+//! hands us an already-opened `mem_fd` for exactly this purpose (see
+//! [`ProxyMessageBuffer`](crate::lxcseccomp::ProxyMessageBuffer)), so all we need on top is a
+//! convenient, TOCTOU-aware way to pull bytes out of it.
+
+use std::mem::{self, MaybeUninit};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use failure::{bail, Error};
+
+use crate::tools::Fd;
+
+/// Maximum length we'll ever copy out for a single C string (a path, typically).
+const MAX_C_STRING: usize = 4096;
+
+/// Checked access to a container process' memory.
+///
+/// This is a thin wrapper around `pread64(2)` on the process' `/proc/pid/mem` file descriptor.
+/// It does *not* by itself make reads safe against the target process running concurrently:
+/// callers must re-validate the seccomp notification via
+/// [`notif_id_valid()`](crate::lxcseccomp::ProxyMessageBuffer::notif_id_valid) after reading and
+/// before acting on the data, since the notification may have gone away (eg. the target task
+/// exited) in the meantime, in which case the bytes copied out don't belong to the syscall being
+/// handled.
+pub struct ProcessMemory<'a> {
+    mem_fd: &'a Fd,
+}
+
+impl<'a> ProcessMemory<'a> {
+    /// Wrap a container's `mem_fd` for checked reads.
+    pub fn new(mem_fd: &'a Fd) -> Self {
+        Self { mem_fd }
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        self.mem_fd.as_raw_fd()
+    }
+
+    /// Read `buf.len()` bytes at `addr` into `buf`.
+    ///
+    /// Fails if the read is short (eg. `addr` points near the end of a mapping) rather than
+    /// silently returning partial data.
+    pub fn pread_into(&self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let mut off = 0usize;
+        while off < buf.len() {
+            let rc = unsafe {
+                libc::pread64(
+                    self.raw_fd(),
+                    buf[off..].as_mut_ptr() as *mut libc::c_void,
+                    buf.len() - off,
+                    (addr as i64)
+                        .checked_add(off as i64)
+                        .ok_or_else(|| failure::format_err!("address overflow"))?,
+                )
+            };
+
+            if rc < 0 {
+                let err = std::io::Error::last_os_error();
+                bail!("failed to read container memory at {:#x}: {}", addr, err);
+            }
+
+            if rc == 0 {
+                bail!(
+                    "short read from container memory at {:#x} ({} of {} bytes)",
+                    addr,
+                    off,
+                    buf.len()
+                );
+            }
+
+            off += rc as usize;
+        }
+
+        Ok(())
+    }
+
+    /// Read a `Copy` struct of type `T` out of container memory.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be valid for any bit pattern (a plain struct of integers, typically a `#[repr(C)]`
+    /// kernel ABI type). This reads raw bytes from the container's memory without any further
+    /// validation of their contents.
+    pub unsafe fn read_struct<T: Copy>(&self, addr: u64) -> Result<T, Error> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let buf = std::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, mem::size_of::<T>());
+        self.pread_into(addr, buf)?;
+        Ok(value.assume_init())
+    }
+
+    /// Read a NUL-terminated string at `addr`, up to `max` bytes including the terminator (so the
+    /// returned string is at most `max - 1` bytes long; `max` is capped at 4096).
+    ///
+    /// Reads in fixed-size chunks rather than byte-by-byte to keep the syscall count down for
+    /// typical path lengths.
+    pub fn read_c_string(&self, addr: u64, max: usize) -> Result<Vec<u8>, Error> {
+        let max = max.min(MAX_C_STRING);
+        let mut out = Vec::new();
+        let mut chunk = [0u8; 256];
+
+        while out.len() < max {
+            let want = chunk.len().min(max - out.len());
+            self.pread_into(addr + out.len() as u64, &mut chunk[..want])?;
+
+            match chunk[..want].iter().position(|&b| b == 0) {
+                Some(nul) => {
+                    out.extend_from_slice(&chunk[..nul]);
+                    return Ok(out);
+                }
+                None => out.extend_from_slice(&chunk[..want]),
+            }
+        }
+
+        bail!("string at {:#x} exceeds maximum length of {}", addr, max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+    /// `pread_into` always reads a full chunk's worth of bytes, same as a real memory mapping
+    /// would (it only ever errors at an actual unmapped page, not a content boundary); pad test
+    /// content with zeroes so `read_c_string`'s chunked reads don't spuriously short-read past a
+    /// backing file's real end the way they never would against real container memory.
+    fn padded(mut content: Vec<u8>, min_len: usize) -> Vec<u8> {
+        content.resize(content.len().max(min_len), 0);
+        content
+    }
+
+    /// Wrap a regular file as a stand-in `mem_fd`; `pread64(2)` works the same on any seekable fd.
+    fn fd_for(content: &[u8]) -> Fd {
+        static COUNTER: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+        let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        let path =
+            std::env::temp_dir().join(format!("mem-test-{}-{}", std::process::id(), n));
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(content).unwrap();
+        std::fs::remove_file(&path).ok(); // unlink now; the open fd keeps the data alive
+        unsafe { Fd::from_raw_fd(file.into_raw_fd()) }
+    }
+
+    #[test]
+    fn pread_into_reads_exact_bytes() {
+        let fd = fd_for(b"hello world");
+        let mem = ProcessMemory::new(&fd);
+
+        let mut buf = [0u8; 5];
+        mem.pread_into(6, &mut buf).unwrap();
+        assert_eq!(&buf, b"world");
+    }
+
+    #[test]
+    fn pread_into_fails_on_short_read() {
+        let fd = fd_for(b"short");
+        let mem = ProcessMemory::new(&fd);
+
+        let mut buf = [0u8; 16];
+        assert!(mem.pread_into(0, &mut buf).is_err());
+    }
+
+    #[test]
+    fn read_c_string_within_one_chunk() {
+        let fd = fd_for(&padded(b"hello\0trailing garbage".to_vec(), 256));
+        let mem = ProcessMemory::new(&fd);
+
+        assert_eq!(mem.read_c_string(0, MAX_C_STRING).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn read_c_string_spans_multiple_chunks() {
+        // 256 is the internal chunk size; put the string past the first chunk boundary so the
+        // second `pread_into` call is exercised.
+        let mut content = vec![b'a'; 300];
+        content.push(0);
+        let content = padded(content, 512);
+        let fd = fd_for(&content);
+        let mem = ProcessMemory::new(&fd);
+
+        assert_eq!(mem.read_c_string(0, 512).unwrap(), vec![b'a'; 300]);
+    }
+
+    #[test]
+    fn read_c_string_nul_exactly_at_chunk_boundary() {
+        let mut content = vec![b'a'; 256];
+        content.push(0);
+        let content = padded(content, 512);
+        let fd = fd_for(&content);
+        let mem = ProcessMemory::new(&fd);
+
+        assert_eq!(mem.read_c_string(0, 512).unwrap(), vec![b'a'; 256]);
+    }
+
+    #[test]
+    fn read_c_string_without_terminator_fails() {
+        let content = vec![b'a'; 64];
+        let fd = fd_for(&content);
+        let mem = ProcessMemory::new(&fd);
+
+        assert!(mem.read_c_string(0, 32).is_err());
+    }
+}
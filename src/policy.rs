@@ -0,0 +1,223 @@
+//! Per-container syscall policy, derived from the `lxc.seccomp.notify.cookie` value.
+//!
+//! Without a policy every connection would get identical handling: whatever syscalls this
+//! daemon knows how to emulate, it would emulate for any container that can reach our socket.
+//! Since the cookie is configured per-container in `lxc.conf`, we use it to let a single daemon
+//! instance serve containers with different privilege profiles - a cookie can either list the
+//! syscalls it wants handled directly (`allow=mount,mknod`), or name a profile to load from
+//! `--policy-dir` (`policy=web-containers`).
+
+use std::fs;
+use std::path::Path;
+
+use failure::{bail, format_err, Error};
+
+/// `AUDIT_ARCH_X86_64`, the only architecture we currently know syscall numbers for.
+///
+/// Extending this to other architectures just means adding more entries to [`syscall_nr`] and to
+/// this list.
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+const KNOWN_ARCHES: &[u32] = &[AUDIT_ARCH_X86_64];
+
+/// Which handler a `(arch, nr)` pair in a client's dispatch table resolves to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Handler {
+    Mount,
+    Mknod,
+    Quotactl,
+}
+
+/// Map a syscall name to its `(arch, nr)` key and handler on a given architecture.
+///
+/// Only lists the syscalls we actually have handlers for (see [`crate::sys_mount`],
+/// `crate::sys_mknod`, `crate::sys_quotactl`).
+fn syscall_nr(arch: u32, name: &str) -> Option<(i32, Handler)> {
+    if arch != AUDIT_ARCH_X86_64 {
+        return None;
+    }
+
+    Some(match name {
+        "mount" => (165, Handler::Mount),
+        "mknod" => (133, Handler::Mknod),
+        "quotactl" => (179, Handler::Quotactl),
+        _ => return None,
+    })
+}
+
+/// A parsed `lxc.seccomp.notify.cookie` directive string.
+///
+/// Fields are `;`-separated `key=value` pairs, eg. `container=foo;allow=mount,mknod`.
+#[derive(Default)]
+struct Directive {
+    allow: Vec<String>,
+    policy_name: Option<String>,
+}
+
+impl Directive {
+    fn parse(text: &str) -> Result<Self, Error> {
+        let mut directive = Directive::default();
+
+        for field in text.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let sep = field
+                .find('=')
+                .ok_or_else(|| format_err!("malformed policy cookie field: {:?}", field))?;
+            let (key, value) = (&field[..sep], &field[sep + 1..]);
+
+            match key {
+                "container" => (), // informational, not currently used for dispatch
+                "allow" => directive.allow.extend(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from),
+                ),
+                "policy" => directive.policy_name = Some(value.to_string()),
+                other => bail!("unknown policy cookie directive: {:?}", other),
+            }
+        }
+
+        Ok(directive)
+    }
+}
+
+/// A per-client syscall dispatch table, built once from the first message's cookie.
+#[derive(Debug)]
+pub struct Policy {
+    enabled: std::collections::HashMap<(u32, i32), Handler>,
+}
+
+impl Policy {
+    /// A policy that enables no handlers at all, so every syscall falls back to `-ENOSYS`.
+    ///
+    /// Used when a client's cookie can't be parsed: it's safer to treat an unreadable policy as
+    /// "no privileges" than to either guess or reject the connection outright.
+    pub fn deny_all() -> Self {
+        Self {
+            enabled: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Parse `cookie` and, if it names a profile, load the allow-list for it from `policy_dir`.
+    ///
+    /// An empty (or non-UTF-8) cookie results in a `Policy` that allows nothing, so that
+    /// containers without an explicit policy keep getting the safe default `-ENOSYS` behavior
+    /// for every syscall. A cookie that fails to parse, or names a profile that can't be loaded,
+    /// returns an error.
+    pub fn from_cookie(cookie: &[u8], policy_dir: Option<&Path>) -> Result<Self, Error> {
+        let text = std::str::from_utf8(cookie).unwrap_or("");
+        if text.is_empty() {
+            return Ok(Self {
+                enabled: std::collections::HashMap::new(),
+            });
+        }
+
+        let directive = Directive::parse(text)?;
+
+        let allow = match directive.policy_name {
+            Some(name) => {
+                let dir = policy_dir.ok_or_else(|| {
+                    format_err!(
+                        "cookie references policy {:?} but no --policy-dir was configured",
+                        name
+                    )
+                })?;
+                load_named_profile(dir, &name)?
+            }
+            None => directive.allow,
+        };
+
+        let mut enabled = std::collections::HashMap::new();
+        for name in &allow {
+            for &arch in KNOWN_ARCHES {
+                if let Some((nr, handler)) = syscall_nr(arch, name) {
+                    enabled.insert((arch, nr), handler);
+                }
+            }
+        }
+
+        Ok(Self { enabled })
+    }
+
+    /// Look up the handler enabled for `(arch, nr)` by the client's policy, if any.
+    pub fn handler_for(&self, arch: u32, nr: i32) -> Option<Handler> {
+        self.enabled.get(&(arch, nr)).copied()
+    }
+}
+
+/// Load a named policy profile from `<policy_dir>/<name>`.
+///
+/// A profile file is a flat, comma- or newline-separated list of syscall names to allow, eg.
+/// `mount,mknod\nquotactl\n`.
+fn load_named_profile(policy_dir: &Path, name: &str) -> Result<Vec<String>, Error> {
+    if name.contains('/') || name.starts_with('.') {
+        bail!("invalid policy name: {:?}", name);
+    }
+
+    let path = policy_dir.join(name);
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format_err!("failed to read policy {:?}: {}", path, e))?;
+
+    Ok(content
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_multi_field_cookie() {
+        let directive = Directive::parse("container=foo;allow=mount, mknod").unwrap();
+        assert_eq!(directive.allow, vec!["mount", "mknod"]);
+        assert_eq!(directive.policy_name, None);
+    }
+
+    #[test]
+    fn parses_policy_field() {
+        let directive = Directive::parse("policy=web-containers").unwrap();
+        assert_eq!(directive.policy_name, Some("web-containers".to_string()));
+    }
+
+    #[test]
+    fn rejects_unknown_directive() {
+        assert!(Directive::parse("frobnicate=yes").is_err());
+    }
+
+    #[test]
+    fn policy_field_without_policy_dir_fails() {
+        let err = Policy::from_cookie(b"policy=web-containers", None).unwrap_err();
+        assert!(err.to_string().contains("no --policy-dir"));
+    }
+
+    #[test]
+    fn policy_field_with_policy_dir_loads_profile() {
+        let dir = std::env::temp_dir().join(format!("policy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("web-containers"), "mount,mknod\n").unwrap();
+
+        let policy = Policy::from_cookie(b"policy=web-containers", Some(&dir)).unwrap();
+        assert_eq!(
+            policy.handler_for(AUDIT_ARCH_X86_64, 165),
+            Some(Handler::Mount)
+        );
+        assert_eq!(
+            policy.handler_for(AUDIT_ARCH_X86_64, 133),
+            Some(Handler::Mknod)
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn load_named_profile_rejects_traversal() {
+        let dir = Path::new("/nonexistent");
+        assert!(load_named_profile(dir, "../foo").is_err());
+        assert!(load_named_profile(dir, ".hidden").is_err());
+        assert!(load_named_profile(dir, "sub/name").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Handler for the `mount(2)` syscall.
+//!
+//! This is the canonical motivating case for seccomp user notification in containers: a
+//! privileged orchestrator can selectively allow a container to mount a filesystem type it would
+//! otherwise be forbidden from mounting (because `CAP_SYS_ADMIN` inside a user namespace isn't
+//! enough for most filesystem drivers), by performing the `mount(2)` itself on the container's
+//! behalf after entering its mount and user namespaces.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStringExt;
+
+use failure::{bail, Error};
+use libc::{c_ulong, pid_t};
+
+use crate::lxcseccomp::ProxyMessageBuffer;
+use crate::mem::ProcessMemory;
+use crate::nsfd::NsFd;
+
+/// Filesystem types we're willing to mount on a container's behalf.
+///
+/// Anything not on this list is rejected outright, without entering any namespace. Keep this
+/// list narrow: every entry here is something we trust the kernel driver for when invoked from
+/// inside (potentially adversarial) container namespaces.
+const ALLOWED_FSTYPES: &[&str] = &["tmpfs", "overlay", "proc", "cgroup", "cgroup2"];
+
+/// Mount flags we pass through as-is. A request carrying any other flag is rejected with `EPERM`.
+///
+/// `MS_BIND` is included deliberately: bind mounts use `fstype` as a don't-care and read
+/// `source` as an existing path rather than a driver name, so they're handled as a special case
+/// in [`fstype_allowed`] regardless of `ALLOWED_FSTYPES`.
+const ALLOWED_FLAGS: c_ulong = (libc::MS_BIND
+    | libc::MS_RDONLY
+    | libc::MS_NOSUID
+    | libc::MS_NODEV
+    | libc::MS_NOEXEC
+    | libc::MS_REC) as c_ulong;
+
+const PATH_MAX: usize = 4096;
+
+struct MountArgs {
+    source: Option<Vec<u8>>,
+    target: Vec<u8>,
+    fstype: Option<Vec<u8>>,
+    flags: c_ulong,
+    data: Option<Vec<u8>>,
+}
+
+fn fstype_allowed(fstype: Option<&[u8]>, flags: c_ulong) -> bool {
+    if flags & libc::MS_BIND as c_ulong != 0 {
+        // Bind mounts re-mount an existing path; `fstype` is ignored by the kernel, so we don't
+        // gate on it.
+        return true;
+    }
+
+    match fstype {
+        Some(fstype) => ALLOWED_FSTYPES
+            .iter()
+            .any(|allowed| allowed.as_bytes() == fstype),
+        None => false,
+    }
+}
+
+fn flags_allowed(flags: c_ulong) -> bool {
+    flags & !ALLOWED_FLAGS == 0
+}
+
+/// Read the `mount(2)` arguments out of the container's memory.
+fn read_args(mem: &ProcessMemory, msg: &ProxyMessageBuffer) -> Result<MountArgs, Error> {
+    let args = &msg.request().data.args;
+
+    let source = if args[0] != 0 {
+        Some(mem.read_c_string(args[0], PATH_MAX)?)
+    } else {
+        None
+    };
+    let target = mem.read_c_string(args[1], PATH_MAX)?;
+    let fstype = if args[2] != 0 {
+        Some(mem.read_c_string(args[2], PATH_MAX)?)
+    } else {
+        None
+    };
+    let flags = args[3] as c_ulong;
+    let data = if args[4] != 0 {
+        Some(mem.read_c_string(args[4], PATH_MAX)?)
+    } else {
+        None
+    };
+
+    Ok(MountArgs {
+        source,
+        target,
+        fstype,
+        flags,
+        data,
+    })
+}
+
+fn to_cstring(bytes: Vec<u8>) -> Result<CString, Error> {
+    CString::new(bytes).map_err(|_| failure::format_err!("path contains an embedded NUL byte"))
+}
+
+/// Perform the actual `mount(2)` call. Must be called after entering the target namespaces.
+fn do_mount(args: &MountArgs) -> Result<(), Error> {
+    let source = match &args.source {
+        Some(s) => Some(to_cstring(s.clone())?),
+        None => None,
+    };
+    let target = to_cstring(args.target.clone())?;
+    let fstype = match &args.fstype {
+        Some(f) => Some(to_cstring(f.clone())?),
+        None => None,
+    };
+    let data = match &args.data {
+        Some(d) => Some(to_cstring(d.clone())?),
+        None => None,
+    };
+
+    let rc = unsafe {
+        libc::mount(
+            source.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            target.as_ptr(),
+            fstype.as_ref().map_or(std::ptr::null(), |f| f.as_ptr()),
+            args.flags,
+            data.as_ref()
+                .map_or(std::ptr::null(), |d| d.as_ptr() as *const libc::c_void),
+        )
+    };
+
+    if rc != 0 {
+        bail!(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Handle an intercepted `mount(2)` notification.
+///
+/// On success the response's `val` is set to `0` and `error` to `0`. On a rejected request or a
+/// failed `mount(2)`, the response's `error` is set to the real negative `errno`, mirroring what
+/// the kernel would have returned had the call not been intercepted.
+pub fn handle(msg: &mut ProxyMessageBuffer, init_pid: pid_t) -> Result<(), Error> {
+    let mem_fd = msg
+        .mem_fd()
+        .ok_or_else(|| failure::format_err!("no mem_fd available for mount() request"))?;
+    let mem = ProcessMemory::new(mem_fd);
+
+    let args = read_args(&mem, msg)?;
+
+    if !msg.notif_id_valid() {
+        bail!("seccomp notification is no longer valid");
+    }
+
+    if !flags_allowed(args.flags) {
+        let resp = msg.response_mut();
+        resp.error = -libc::EPERM;
+        resp.val = -1;
+        return Ok(());
+    }
+
+    if !fstype_allowed(args.fstype.as_deref(), args.flags) {
+        let resp = msg.response_mut();
+        resp.error = -libc::EPERM;
+        resp.val = -1;
+        return Ok(());
+    }
+
+    let ns = NsFd::open(init_pid, &["mnt", "user"])?;
+
+    let result = ns.fork_and_run(|| do_mount(&args))?;
+
+    let resp = msg.response_mut();
+    match result {
+        Ok(()) => {
+            resp.error = 0;
+            resp.val = 0;
+        }
+        Err(err) => {
+            resp.error = -errno_of(&err);
+            resp.val = -1;
+        }
+    }
+
+    Ok(())
+}
+
+fn errno_of(err: &Error) -> i32 {
+    match err.downcast_ref::<std::io::Error>() {
+        Some(ioerr) => ioerr.raw_os_error().unwrap_or(libc::EIO),
+        None => libc::EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unlisted_fstype() {
+        assert!(!fstype_allowed(Some(b"vfat"), 0));
+        assert!(!fstype_allowed(None, 0));
+    }
+
+    #[test]
+    fn allows_listed_fstype() {
+        assert!(fstype_allowed(Some(b"tmpfs"), 0));
+    }
+
+    #[test]
+    fn bind_mount_ignores_fstype_allowlist() {
+        assert!(fstype_allowed(Some(b"whatever"), libc::MS_BIND as c_ulong));
+        assert!(fstype_allowed(None, libc::MS_BIND as c_ulong));
+    }
+
+    #[test]
+    fn rejects_disallowed_flags() {
+        assert!(!flags_allowed(libc::MS_REMOUNT as c_ulong));
+    }
+
+    #[test]
+    fn successful_bind_mount() {
+        // Bind-mounting a directory onto itself requires CAP_SYS_ADMIN (or a user namespace with
+        // it); skip if we don't have it rather than failing CI on unprivileged runners.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("sys_mount-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let args = MountArgs {
+            source: Some(dir.clone().into_os_string().into_vec()),
+            target: dir.clone().into_os_string().into_vec(),
+            fstype: None,
+            flags: libc::MS_BIND as c_ulong,
+            data: None,
+        };
+
+        let result = do_mount(&args);
+        let _ = unsafe { libc::umount(to_cstring(dir.clone().into_os_string().into_vec()).unwrap().as_ptr()) };
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(result.is_ok(), "bind mount failed: {:?}", result.err());
+    }
+}